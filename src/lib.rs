@@ -9,19 +9,29 @@
 //!
 //! # Theory of Operation
 //!
-//! The implementation uses a large index vector of
-//! initially uninitialized memory, together with a stack of
-//! stored values. As such, it will occupy space
-//! proportional to its capacity, and additional space
-//! proportional to the number of stored elements.
+//! The implementation uses a large, zero-initialized index
+//! vector, together with a stack of stored values. As such,
+//! it will occupy space proportional to its capacity, and
+//! additional space proportional to the number of stored
+//! elements.
 //!
-//! The basic strategy of this data structure is to keep an
-//! initially-uninitialized index vector and a stack of
-//! allocated values.  A given index has a valid value if
-//! its index vector points into the stack and the stack
-//! element it points to shows the same index. Otherwise,
-//! the data structure can be adjusted to make this true,
-//! creating a default value as needed.
+//! The basic strategy of this data structure is to keep a
+//! zero-initialized index vector and a stack of allocated
+//! values.  A given index has a valid value if its index
+//! vector points into the stack and the stack element it
+//! points to shows the same index. Otherwise, the data
+//! structure can be adjusted to make this true, creating a
+//! default value as needed.
+//!
+//! The index vector is zero-initialized, rather than left
+//! truly uninitialized, because current Rust considers
+//! reading uninitialized memory instant undefined behavior
+//! even for a type like `usize` that accepts every bit
+//! pattern: Miri tracks initializedness per byte, not just
+//! per type. Zero-initializing a large `Vec` is backed by the
+//! allocator's zeroed-page fast path, so it remains cheap in
+//! practice even though it is no longer, strictly speaking,
+//! skipped.
 //!
 //! ## Data Structure
 //! 
@@ -31,9 +41,11 @@
 //!   the array.
 //! 
 //! * Every initialized entry in the array points back to
-//! its corresponding entry on the stack. (The uninitialized
-//! entries, obviously, could point anywhere, including onto
-//! the stack.)
+//! its corresponding entry on the stack. (The not-yet-written
+//! entries are all zero, so they point at stack slot zero,
+//! which either does not exist yet or holds the entry of some
+//! other index; they are thus correctly treated as
+//! uninitialized.)
 //! 
 //! ## To read from the array:
 //! 
@@ -49,9 +61,9 @@
 //!    the array uses vast amounts of VM and the stack will
 //!    be limited-size.)
 //!  
-//!    * If the array pointer is invalid or the stack
-//!    pointer doesn't match it, the array element is
-//!    uninitialized. Throw an error. (Alternatively,
+//!    * If the array pointer is out of range or the stack
+//!    pointer doesn't match it, the array element has not
+//!    been written yet. Throw an error. (Alternatively,
 //!    initialize as below with some default value and
 //!    return that.)
 //! 
@@ -67,21 +79,80 @@
 //! 
 //! Note that every operation is constant-time and consumes
 //! constant space. (We will agree to ignore the giant pile
-//! of uninitialized virtual memory lying in the corner.)
+//! of virtual memory lying in the corner, zeroed or not.)
 //! Thus, our efficiency is as good (in some sense) as a
-//! normal array write. But *we don't have to initialize the
-//! giant array first,* which is great if the array is going
-//! to be really sparsely filled.
+//! normal array write. But *we don't have to visit the giant
+//! array first,* which is great if the array is going to be
+//! really sparsely filled. The one exception is growing the
+//! capacity (via `reserve`, or a `set` or mutable index past
+//! the current capacity), which copies the index region and
+//! so takes time linear in the capacity; as with `Vec`, the
+//! capacity at least doubles on each growth, so this cost is
+//! amortized.
+//!
+//! ## Serialization
+//!
+//! With the `serde` feature enabled, an `SIVec` serializes as
+//! just its capacity plus the sparse `(index, value)` pairs
+//! that have been written, never the index region. Since the
+//! initializer is a closure and cannot be serialized, plain
+//! deserialization produces an `SIVec` that panics on reads of
+//! never-written indices, like [`SIVec::new`]; use
+//! [`deserialize_with_init_fn`] to supply one instead.
 
-use std::cell::RefCell;
+use std::alloc::{self, Layout};
+use std::cell::{Ref, RefCell};
+use std::collections::TryReserveError;
 use std::isize;
 use std::ops::{Index, IndexMut};
+use std::slice;
+use std::vec;
+
+#[cfg(feature = "serde")]
+use serde::de::Error as _;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 struct Value<T> {
     value: T,
     index: usize,
 }
 
+// Allocate a zero-filled index region of `len` `usize`s, the way
+// `vec![0; len]` does, through the allocator's zeroed-page fast
+// path, rather than reserving plain capacity and then zeroing it
+// element by element: the latter forces every newly committed page
+// to be physically touched up front, which is disastrous for the
+// large capacities these fallible constructors exist to handle
+// (observed ~40000x slower for a 200M-entry region in testing).
+// Reports allocation failure as `Err` instead of aborting.
+fn try_zeroed_index_vec(len: usize) -> Result<Vec<usize>, TryReserveError> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    // `alloc_zeroed` itself can only report failure as a null
+    // pointer, not as a `TryReserveError` (which has no public
+    // constructor); probe with an ordinary fallible reservation of
+    // the same size first so a genuine allocation failure surfaces
+    // as the `Err` this function promises. Nothing is written to
+    // the probe allocation, which is freed at once, so this costs
+    // only address space, not time.
+    Vec::<usize>::new().try_reserve_exact(len)?;
+    let layout = Layout::array::<usize>(len).expect("SIVec: capacity overflow");
+    // SAFETY: `layout` is non-zero-sized since `len > 0`, and
+    // `alloc_zeroed` returns either null or a zero-initialized
+    // allocation of exactly `layout`'s size, which together with
+    // `len == capacity == len` is what `Vec::from_raw_parts`
+    // requires.
+    let ptr = unsafe { alloc::alloc_zeroed(layout) };
+    if ptr.is_null() {
+        // The probe above already makes this essentially
+        // unreachable; treat it like any other Rust allocator
+        // failure rather than invent a synthetic `TryReserveError`.
+        alloc::handle_alloc_error(layout);
+    }
+    Ok(unsafe { Vec::from_raw_parts(ptr as *mut usize, len, len) })
+}
 
 /// A "self-initializing" vector.
 pub struct SIVec<T> {
@@ -89,8 +160,15 @@ pub struct SIVec<T> {
     // of `Index::index`, which takes `self` as an immutable
     // reference.
     value_stack: RefCell<Vec<Value<T>>>,
-    vec: Vec<usize>,
+    // Needs interior mutability for the same reason as
+    // `value_stack` above: `get_mut_ref`, which only sees
+    // `&self`, writes the index cell of a newly stored value.
+    vec: RefCell<Vec<usize>>,
     initializer: Box<dyn Fn(usize) -> T + 'static>,
+    // Only populated when tracking is enabled by
+    // `with_tracking`; otherwise always empty.
+    tracking: bool,
+    events: RefCell<Vec<Event>>,
 }
 
 impl<T> SIVec<T> {
@@ -107,13 +185,38 @@ impl<T> SIVec<T> {
         assert!(cap <= isize::MAX as usize);
         SIVec {
             value_stack: RefCell::new(Vec::new()),
-            vec: Vec::with_capacity(cap),
+            vec: RefCell::new(vec![0; cap]),
             initializer: Box::new(|_| {
                 panic!("no initializer for SIVec")
             }),
+            tracking: false,
+            events: RefCell::new(Vec::new()),
         }
     }
 
+    /// Like [`SIVec::new`], but reports an allocation
+    /// failure as an `Err` instead of aborting the process.
+    /// This is useful when the capacity is derived from
+    /// untrusted input and may be unreasonably large.
+    ///
+    /// # Panics
+    ///
+    /// Will panic with a failed assertion if called with a
+    /// capacity exceeding the allowed bound.
+    pub fn try_new(cap: usize) -> Result<SIVec<T>, TryReserveError> {
+        assert!(cap <= isize::MAX as usize);
+        let vec = try_zeroed_index_vec(cap)?;
+        Ok(SIVec {
+            value_stack: RefCell::new(Vec::new()),
+            vec: RefCell::new(vec),
+            initializer: Box::new(|_| {
+                panic!("no initializer for SIVec")
+            }),
+            tracking: false,
+            events: RefCell::new(Vec::new()),
+        })
+    }
+
     /// Create a new `SIVec` with the given (fixed)
     /// capacity. If a given index is read before first
     /// write, a clone of the given default value will be
@@ -131,11 +234,37 @@ impl<T> SIVec<T> {
         assert!(cap <= isize::MAX as usize);
         SIVec {
             value_stack: RefCell::new(Vec::new()),
-            vec: Vec::with_capacity(cap),
+            vec: RefCell::new(vec![0; cap]),
             initializer: Box::new(move |_| value.clone()),
+            tracking: false,
+            events: RefCell::new(Vec::new()),
         }
     }
 
+    /// Like [`SIVec::with_init`], but reports an allocation
+    /// failure as an `Err` instead of aborting the process.
+    /// This is useful when the capacity is derived from
+    /// untrusted input and may be unreasonably large.
+    ///
+    /// # Panics
+    ///
+    /// Will panic with a failed assertion if called with a
+    /// capacity exceeding the allowed bound.
+    pub fn try_with_init(cap: usize, value: T) -> Result<SIVec<T>, TryReserveError>
+    where
+        T: Clone + 'static,
+    {
+        assert!(cap <= isize::MAX as usize);
+        let vec = try_zeroed_index_vec(cap)?;
+        Ok(SIVec {
+            value_stack: RefCell::new(Vec::new()),
+            vec: RefCell::new(vec),
+            initializer: Box::new(move |_| value.clone()),
+            tracking: false,
+            events: RefCell::new(Vec::new()),
+        })
+    }
+
     /// Create a new `SIVec` with the given (fixed)
     /// capacity. If a given index `i` is read before first
     /// write, the `init_fn` will be called with `i` to get
@@ -153,8 +282,67 @@ impl<T> SIVec<T> {
         assert!(cap <= isize::MAX as usize);
         SIVec {
             value_stack: RefCell::new(Vec::new()),
-            vec: Vec::with_capacity(cap),
+            vec: RefCell::new(vec![0; cap]),
+            initializer: Box::new(init_fn),
+            tracking: false,
+            events: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Like [`SIVec::with_init_fn`], but reports an
+    /// allocation failure as an `Err` instead of aborting
+    /// the process. This is useful when the capacity is
+    /// derived from untrusted input and may be unreasonably
+    /// large.
+    ///
+    /// # Panics
+    ///
+    /// Will panic with a failed assertion if called with a
+    /// capacity exceeding the allowed bound.
+    pub fn try_with_init_fn<F>(cap: usize, init_fn: F) -> Result<SIVec<T>, TryReserveError>
+    where
+        F: Fn(usize) -> T + 'static,
+    {
+        assert!(cap <= isize::MAX as usize);
+        let vec = try_zeroed_index_vec(cap)?;
+        Ok(SIVec {
+            value_stack: RefCell::new(Vec::new()),
+            vec: RefCell::new(vec),
+            initializer: Box::new(init_fn),
+            tracking: false,
+            events: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Create a new `SIVec` with the given initial capacity
+    /// and change tracking enabled. Like
+    /// [`SIVec::with_init_fn`], `init_fn` is called with `i`
+    /// to get a default value when index `i` is read before
+    /// first write. In addition, every first write to an
+    /// index (whether via `set`, `get`, indexing, or the
+    /// initializer) pushes an [`Event::Inserted`], every
+    /// later write to an already-set index via `set` or
+    /// indexing pushes an [`Event::Modified`], and every
+    /// [`SIVec::unset`] of a set index pushes an
+    /// [`Event::Removed`]. Collect these with
+    /// [`SIVec::drain_events`]. The maximum allowed capacity
+    /// is `std::isize::MAX`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic with a failed assertion if called with a
+    /// capacity exceeding the allowed bound.
+    pub fn with_tracking<F>(cap: usize, init_fn: F) -> SIVec<T>
+    where
+        F: Fn(usize) -> T + 'static,
+    {
+        assert!(cap <= isize::MAX as usize);
+        SIVec {
+            value_stack: RefCell::new(Vec::new()),
+            vec: RefCell::new(vec![0; cap]),
             initializer: Box::new(init_fn),
+            tracking: true,
+            events: RefCell::new(Vec::new()),
         }
     }
 
@@ -163,29 +351,28 @@ impl<T> SIVec<T> {
     // notionally at the given `index`.
     //
     // In the case that this is a reference to a
-    // previously-uninitialized location, the behavior of
-    // this function will depend on the `value` argument. If
-    // value is `None`, the storage will be initialized with
-    // a value obtained from the `self` initializer,
-    // panicking if no initializer was provided. Otherwise,
-    // the storage will be initialized with the given value.
-    fn get_mut_ref(&self, index: usize, value: Option<T>) -> *mut T {
-        if index >= self.vec.capacity() {
-            panic!("SIVec: index bounds");
-        }
-        let store = self.vec.as_ptr() as *mut usize;
-        // This offset will not overflow. The capacity is
-        // guaranteed to be less than `isize::MAX` by the
-        // constructors, and we have checked the bound
-        // above.
-        let ip = unsafe { store.add(index) };
-        // XXX Need to do an unsafe read because
-        // all we have is a raw pointer.
-        // XXX Miri is not happy with this read, since
-        // the memory is known-undefined. I don't think
-        // there's much to be done about this given the
-        // current Rust UB rules.
-        let si = unsafe { *ip };
+    // previously-unwritten location, the behavior of this
+    // function will depend on the `value` argument. If value
+    // is `None`, the storage will be initialized with a
+    // value obtained from the `self` initializer, panicking
+    // if no initializer was provided. Otherwise, the storage
+    // will be initialized with the given value.
+    //
+    // `is_write` tells whether the caller is obtaining the
+    // reference in order to write through it, as opposed to
+    // merely reading it; this can't be inferred from `value`
+    // alone, since `index_mut` returns a reference the caller
+    // may write through without ever passing a `value` here.
+    // It is used only to decide whether to record an
+    // `Event::Modified` when change tracking is enabled.
+    fn get_mut_ref(&self, index: usize, value: Option<T>, is_write: bool) -> *mut T {
+        let si = {
+            let vec = self.vec.borrow();
+            if index >= vec.len() {
+                panic!("SIVec: index bounds");
+            }
+            vec[index]
+        };
         let mut value_stack = self.value_stack.borrow_mut();
         let vsl = value_stack.len();
         if si < vsl && value_stack[si].index == index {
@@ -193,6 +380,9 @@ impl<T> SIVec<T> {
             if let Some(value) = value {
                 *vp = value;
             }
+            if is_write {
+                self.push_event(Event::Modified(index));
+            }
             // XXX The value is guaranteed to live as long
             // as the borrow of self, by construction of
             // this datatype.
@@ -204,18 +394,28 @@ impl<T> SIVec<T> {
         };
         let value = Value { value, index };
         value_stack.push(value);
-        // XXX Initialize the index.
-        unsafe { *ip = vsl };
+        self.vec.borrow_mut()[index] = vsl;
+        self.push_event(Event::Inserted(index));
         &mut value_stack[vsl].value
     }
 
+    // Record `event` in the event buffer if tracking is
+    // enabled; otherwise do nothing.
+    fn push_event(&self, event: Event) {
+        if self.tracking {
+            self.events.borrow_mut().push(event);
+        }
+    }
+
     /// Set the given location to have the given value.
     /// This is potentially more efficient than storing
     /// through an index: in the index case, a default value
     /// will be created and then immediately replaced.
     /// For the same reason, this is the only way to
     /// initially set a value at a given index when no
-    /// default has been supplied.
+    /// default has been supplied. If `index` is beyond the
+    /// current capacity, the index region is grown to fit,
+    /// as if by [`SIVec::reserve`].
     ///
     /// # Examples
     ///
@@ -225,7 +425,8 @@ impl<T> SIVec<T> {
     /// assert_eq!(v[3], 'a');
     /// ```
     pub fn set(&mut self, index: usize, value: T) {
-        let _ = self.get_mut_ref(index, Some(value));
+        self.ensure_capacity(index);
+        let _ = self.get_mut_ref(index, Some(value), true);
     }
 
     /// Get an immutable reference to the location holding
@@ -243,21 +444,320 @@ impl<T> SIVec<T> {
     /// assert_eq!(*v.get(3), 'a');
     /// ```
     pub fn get(&self, index: usize) -> &T {
-        let ptr = self.get_mut_ref(index, None);
+        let ptr = self.get_mut_ref(index, None, false);
         unsafe { ptr.as_ref() }.unwrap()
     }
 
     /// Report the capacity of this structure.
     pub fn cap(&self) -> usize {
-        self.vec.capacity()
+        self.vec.borrow().len()
+    }
+
+    /// Reserve capacity for at least `additional` more
+    /// indices beyond the current capacity, growing the
+    /// index region amortized the way `Vec` does (capacity
+    /// at least doubles each time it must grow, up to
+    /// `std::isize::MAX`). This is sound because the index
+    /// region holds plain indices into `value_stack`, not
+    /// pointers, so copying it into a larger buffer preserves
+    /// every existing entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the required capacity would exceed
+    /// `std::isize::MAX`.
+    pub fn reserve(&mut self, additional: usize) {
+        let cap = self.vec.get_mut().len();
+        let required = cap
+            .checked_add(additional)
+            .expect("SIVec: capacity overflow");
+        if required > cap {
+            self.grow_to(required);
+        }
+    }
+
+    // Grow the index region to at least `required` capacity,
+    // preserving its existing contents. `required` may exceed
+    // what doubling alone would give, e.g. when a single
+    // out-of-range `set` demands a big jump. New cells are
+    // zero-filled, matching the invariant that every index
+    // cell is always initialized.
+    fn grow_to(&mut self, required: usize) {
+        let max_cap = isize::MAX as usize;
+        assert!(required <= max_cap, "SIVec: capacity overflow");
+        let vec = self.vec.get_mut();
+        let cap = vec.len();
+        let new_cap = cap.saturating_mul(2).max(required).min(max_cap);
+        // Build the new region as a fresh `vec![0; new_cap]`,
+        // which (like `try_zeroed_index_vec` above) gets the
+        // allocator's zeroed-page fast path, then copy the live
+        // prefix in. Growing `vec` in place with `resize` would
+        // not get that fast path, since `resize` zeroes the new
+        // elements one at a time, which is ruinous for a region
+        // that can be gigabytes in size.
+        let mut new_vec = vec![0; new_cap];
+        new_vec[..cap].copy_from_slice(vec);
+        *vec = new_vec;
+    }
+
+    // Grow the index region, if necessary, so that `index` is
+    // in bounds.
+    fn ensure_capacity(&mut self, index: usize) {
+        let cap = self.vec.get_mut().len();
+        if index >= cap {
+            self.reserve(index.saturating_add(1).saturating_sub(cap));
+        }
+    }
+
+    /// Report the number of indices that currently hold a
+    /// value: those written or default-initialized and not
+    /// since removed by `unset` or `clear`. This is O(1).
+    pub fn len(&self) -> usize {
+        self.value_stack.borrow().len()
+    }
+
+    /// Return `true` if no index currently holds a value.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the `(index, value)` pairs that have
+    /// actually been written, in unspecified order. This
+    /// is O(number of set elements), never touching the
+    /// index region.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            stack: self.value_stack.borrow(),
+            pos: 0,
+        }
+    }
+
+    /// Mutably iterate over the `(index, value)` pairs that
+    /// have actually been written, in unspecified order.
+    /// This is O(number of set elements).
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            iter: self.value_stack.get_mut().iter_mut(),
+        }
+    }
+
+    /// Remove the value at the given `index`, if any, and
+    /// return it. This is O(1): the removed entry's slot in
+    /// the value stack is filled by swapping in the last
+    /// entry, whose index cell is then repointed at the
+    /// vacated slot. The old index's stale index cell is
+    /// left untouched, since it will simply fail the
+    /// liveness check on the next access.
+    pub fn unset(&mut self, index: usize) -> Option<T> {
+        let vec = self.vec.get_mut();
+        if index >= vec.len() {
+            return None;
+        }
+        let si = vec[index];
+        let value_stack = self.value_stack.get_mut();
+        let vsl = value_stack.len();
+        if si >= vsl || value_stack[si].index != index {
+            return None;
+        }
+        let last = vsl - 1;
+        value_stack.swap(si, last);
+        let removed = value_stack.pop().unwrap();
+        if si != last {
+            let moved_index = value_stack[si].index;
+            vec[moved_index] = si;
+        }
+        if self.tracking {
+            self.events.get_mut().push(Event::Removed(index));
+        }
+        Some(removed.value)
+    }
+
+    /// Remove all values, leaving the `SIVec` empty. This
+    /// is O(1): the stale index cells of the removed values
+    /// are left in place, since they will simply fail the
+    /// liveness check on the next access. No `Event::Removed`
+    /// is pushed for the cleared values even when tracking is
+    /// enabled, since enumerating them would defeat the O(1)
+    /// bound.
+    pub fn clear(&mut self) {
+        self.value_stack.get_mut().clear();
+    }
+
+    /// Drain and return the events recorded since the last
+    /// call to `drain_events`, oldest first. If tracking was
+    /// not enabled via [`SIVec::with_tracking`], this is
+    /// always empty.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, Event> {
+        self.events.get_mut().drain(..)
+    }
+}
+
+/// An event recording a change made to an [`SIVec`] created
+/// with [`SIVec::with_tracking`], as returned by
+/// [`SIVec::drain_events`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A value was written to a previously-unset index for
+    /// the first time.
+    Inserted(usize),
+    /// An already-set index was overwritten.
+    Modified(usize),
+    /// A set index was removed via [`SIVec::unset`].
+    Removed(usize),
+}
+
+/// Iterator over the `(index, value)` pairs of an
+/// [`SIVec`], produced by [`SIVec::iter`].
+pub struct Iter<'a, T> {
+    stack: Ref<'a, Vec<Value<T>>>,
+    pos: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.stack.get(self.pos)?;
+        self.pos += 1;
+        // XXX The returned reference is guaranteed to live
+        // as long as the borrow of self, by construction of
+        // this datatype, so it is safe to extend it from
+        // the lifetime of `self.stack` to `'a`.
+        let value: *const T = &entry.value;
+        Some((entry.index, unsafe { &*value }))
+    }
+}
+
+/// Mutable iterator over the `(index, value)` pairs of an
+/// [`SIVec`], produced by [`SIVec::iter_mut`].
+pub struct IterMut<'a, T> {
+    iter: slice::IterMut<'a, Value<T>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (usize, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.iter.next()?;
+        Some((entry.index, &mut entry.value))
+    }
+}
+
+/// Consuming iterator over the `(index, value)` pairs of an
+/// [`SIVec`], produced by `SIVec::into_iter`.
+pub struct IntoIter<T> {
+    iter: vec::IntoIter<Value<T>>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = (usize, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|entry| (entry.index, entry.value))
+    }
+}
+
+impl<T> IntoIterator for SIVec<T> {
+    type Item = (usize, T);
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter {
+            iter: self.value_stack.into_inner().into_iter(),
+        }
+    }
+}
+
+/// On-the-wire representation of an [`SIVec`]: its capacity
+/// plus the sparse `(index, value)` pairs that have actually
+/// been written. The (potentially huge) index region is never
+/// serialized, since it is recomputable from `entries`.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SIVecData<T> {
+    cap: usize,
+    entries: Vec<(usize, T)>,
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize> Serialize for SIVec<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let data = SIVecData {
+            cap: self.cap(),
+            entries: self.iter().collect(),
+        };
+        data.serialize(serializer)
+    }
+}
+
+/// Deserializing an [`SIVec`] this way reconstructs its
+/// capacity and sparse contents, but not its initializer
+/// (which cannot itself be serialized): the result behaves
+/// like one from [`SIVec::new`], panicking on reads of indices
+/// that were never written. Use [`deserialize_with_init_fn`]
+/// instead if that is not what you want.
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for SIVec<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data = SIVecData::<T>::deserialize(deserializer)?;
+        let cap = data.cap;
+        let mut v = SIVec::new(cap);
+        for (index, value) in data.entries {
+            if index >= cap {
+                return Err(D::Error::custom(format!(
+                    "SIVec: entry index {} out of range for capacity {}",
+                    index, cap
+                )));
+            }
+            v.set(index, value);
+        }
+        Ok(v)
+    }
+}
+
+/// Deserialize an [`SIVec`] as [`Deserialize::deserialize`]
+/// does, but with `init_fn` supplied as the initializer for
+/// indices that are read before being written, rather than
+/// panicking. This is the only way to get a usable initializer
+/// back onto a deserialized `SIVec`, since the original
+/// initializer is a closure and so cannot round-trip through
+/// serialization itself.
+#[cfg(feature = "serde")]
+pub fn deserialize_with_init_fn<'de, D, T, F>(
+    deserializer: D,
+    init_fn: F,
+) -> Result<SIVec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+    F: Fn(usize) -> T + 'static,
+{
+    let data = SIVecData::<T>::deserialize(deserializer)?;
+    let cap = data.cap;
+    let mut v = SIVec::with_init_fn(cap, init_fn);
+    for (index, value) in data.entries {
+        if index >= cap {
+            return Err(D::Error::custom(format!(
+                "SIVec: entry index {} out of range for capacity {}",
+                index, cap
+            )));
+        }
+        v.set(index, value);
     }
+    Ok(v)
 }
 
 impl<T> Index<usize> for SIVec<T> {
     type Output = T;
 
     fn index(&self, index: usize) -> &T {
-        let ptr = self.get_mut_ref(index, None);
+        let ptr = self.get_mut_ref(index, None, false);
         unsafe { ptr.as_ref() }.unwrap()
     }
 }
@@ -267,7 +767,8 @@ impl<T> IndexMut<usize> for SIVec<T> {
         // XXX Since we can't know whether the caller
         // will initialize the value, we need to
         // provide a default value before returning.
-        let ptr = self.get_mut_ref(index, None);
+        self.ensure_capacity(index);
+        let ptr = self.get_mut_ref(index, None, true);
         unsafe { ptr.as_mut() }.unwrap()
     }
 }
@@ -299,3 +800,172 @@ fn basic_test() {
     assert_eq!(v[0], 'a');
     assert_eq!(v[2], 'c');
 }
+
+#[test]
+fn try_new_test() {
+    let mut v = SIVec::try_new(10).unwrap();
+    v.set(3, 'a');
+    assert_eq!(v[3], 'a');
+
+    let v = SIVec::try_with_init(10, 'b').unwrap();
+    assert_eq!(v[4], 'b');
+
+    let init = |i| std::char::from_u32('a' as u32 + i as u32).unwrap();
+    let v = SIVec::try_with_init_fn(10, init).unwrap();
+    assert_eq!(v[0], 'a');
+    assert_eq!(v[2], 'c');
+}
+
+#[test]
+fn iter_test() {
+    let mut v = SIVec::new(10);
+    assert!(v.is_empty());
+    assert_eq!(v.len(), 0);
+
+    v.set(3, 'a');
+    v.set(7, 'b');
+    assert_eq!(v.len(), 2);
+
+    let mut seen: Vec<(usize, char)> =
+        v.iter().map(|(i, c)| (i, *c)).collect();
+    seen.sort();
+    assert_eq!(seen, vec![(3, 'a'), (7, 'b')]);
+
+    for (_, c) in v.iter_mut() {
+        *c = 'z';
+    }
+    assert_eq!(v[3], 'z');
+    assert_eq!(v[7], 'z');
+
+    let mut seen: Vec<(usize, char)> = v.into_iter().collect();
+    seen.sort();
+    assert_eq!(seen, vec![(3, 'z'), (7, 'z')]);
+}
+
+#[test]
+fn unset_test() {
+    let mut v = SIVec::new(10);
+    assert_eq!(v.unset(3), None);
+
+    v.set(3, 'a');
+    v.set(5, 'b');
+    v.set(7, 'c');
+    assert_eq!(v.len(), 3);
+
+    assert_eq!(v.unset(5), Some('b'));
+    assert_eq!(v.len(), 2);
+    assert_eq!(v.unset(5), None);
+
+    let mut seen: Vec<(usize, char)> = v.iter().map(|(i, c)| (i, *c)).collect();
+    seen.sort();
+    assert_eq!(seen, vec![(3, 'a'), (7, 'c')]);
+
+    v.clear();
+    assert!(v.is_empty());
+    assert_eq!(v.unset(3), None);
+
+    v.set(3, 'z');
+    assert_eq!(v[3], 'z');
+}
+
+#[test]
+fn tracking_test() {
+    let mut v = SIVec::with_tracking(10, |_| 'x');
+    let _ = v[3]; // first read: inserts a default value
+    v.set(3, 'a'); // overwrite via set: modifies
+    v[5] = 'b'; // first write via index_mut: inserts a default, then the
+                // assignment itself isn't visible to get_mut_ref
+    v[3] = 'c'; // overwrite via index_mut: modifies
+    let _ = v.get(5); // read of an already-set index: no event
+    v.unset(3);
+    assert_eq!(
+        v.drain_events().collect::<Vec<_>>(),
+        vec![
+            Event::Inserted(3),
+            Event::Modified(3),
+            Event::Inserted(5),
+            Event::Modified(3),
+            Event::Removed(3),
+        ]
+    );
+    assert_eq!(v.drain_events().collect::<Vec<_>>(), vec![]);
+
+    let mut v = SIVec::new(10);
+    v.set(3, 'a');
+    assert_eq!(v.drain_events().collect::<Vec<_>>(), vec![]);
+}
+
+#[test]
+fn reserve_test() {
+    let mut v = SIVec::new(4);
+    v.set(3, 'a');
+    assert_eq!(v.cap(), 4);
+
+    v.set(100, 'b');
+    assert!(v.cap() > 100);
+    assert_eq!(v[3], 'a');
+    assert_eq!(v[100], 'b');
+
+    let mut v = SIVec::with_init(4, 'z');
+    v[10] = 'y';
+    assert!(v.cap() > 10);
+    assert_eq!(v[10], 'y');
+    assert_eq!(v[5], 'z');
+
+    let mut v: SIVec<char> = SIVec::new(4);
+    v.reserve(20);
+    assert!(v.cap() >= 24);
+}
+
+#[test]
+#[should_panic(expected = "SIVec: capacity overflow")]
+fn ensure_capacity_overflow_test() {
+    // `index + 1` must not be computed with a plain `+`: on a
+    // small-capacity `SIVec`, `index == usize::MAX` would overflow
+    // that addition before `reserve` ever gets a chance to reject
+    // it as exceeding `isize::MAX`.
+    let mut v: SIVec<char> = SIVec::new(4);
+    v.set(usize::MAX, 'a');
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_roundtrip_test() {
+    let mut v = SIVec::new(10);
+    v.set(3, 'a');
+    v.set(7, 'b');
+
+    let json = serde_json::to_string(&v).unwrap();
+    let v: SIVec<char> = serde_json::from_str(&json).unwrap();
+    assert_eq!(v.cap(), 10);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[3], 'a');
+    assert_eq!(v[7], 'b');
+
+    let err = serde_json::from_str::<SIVec<char>>(r#"{"cap":1,"entries":[[5,"a"]]}"#)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("out of range"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_deserialize_with_init_fn_test() {
+    let mut v = SIVec::with_init_fn(10, |_| 'z');
+    v.set(3, 'a');
+
+    let json = serde_json::to_string(&v).unwrap();
+    let v: SIVec<char> =
+        deserialize_with_init_fn(&mut serde_json::Deserializer::from_str(&json), |_| 'z')
+            .unwrap();
+    assert_eq!(v[3], 'a');
+    assert_eq!(v[4], 'z');
+
+    let err = deserialize_with_init_fn(
+        &mut serde_json::Deserializer::from_str(r#"{"cap":1,"entries":[[5,"a"]]}"#),
+        |_| 'z',
+    )
+    .err()
+    .unwrap();
+    assert!(err.to_string().contains("out of range"));
+}